@@ -0,0 +1,150 @@
+//! An interactive REPL for generating `pest-test` test cases from a live grammar.
+//!
+//! Loads a `.pest` grammar file and a start rule, then repeatedly reads a code snippet (multiple
+//! lines, terminated by a blank line or EOF), parses it with that grammar, and prints the
+//! resulting tree in `pest-test`'s s-expression syntax so it can be pasted directly into a test
+//! file's expected block.
+//!
+//! ```text
+//! pest-test-repl grammar.pest source_file [--skip RULE]... [--transparent RULE]... [--collapse]
+//! ```
+//!
+//! `--skip`/`--transparent`/`--collapse` mirror [`TreeBuilderConfig`]'s fields, so the tree
+//! printed here is normalized the same way a real test's `build_actual` call would normalize it.
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use colored::Color;
+use pest_test::model::{Expression, ExpressionFormatter, TreeBuilderConfig};
+use pest_vm::Vm;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (grammar_path, start_rule) = match (args.next(), args.next()) {
+        (Some(grammar_path), Some(start_rule)) => (grammar_path, start_rule),
+        _ => {
+            eprintln!(
+                "Usage: pest-test-repl <grammar.pest> <start-rule> \
+                 [--skip RULE]... [--transparent RULE]... [--collapse]"
+            );
+            std::process::exit(1);
+        }
+    };
+    let config = parse_config(args);
+
+    let grammar = fs::read_to_string(&grammar_path)
+        .unwrap_or_else(|error| panic!("Failed to read {grammar_path}: {error}"));
+    let (_, rules) = pest_meta::parse_and_optimize(&grammar)
+        .unwrap_or_else(|errors| panic!("Failed to parse {grammar_path}: {errors:?}"));
+    let vm = Vm::new(rules);
+
+    let color_on_tty = io::stdout().is_terminal().then_some(Color::Cyan);
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().ok();
+        let snippet = match read_snippet(&stdin) {
+            Some(snippet) => snippet,
+            None => break,
+        };
+        if snippet.trim().is_empty() {
+            continue;
+        }
+        print_parse(&vm, &start_rule, &snippet, &config, color_on_tty);
+    }
+}
+
+/// Builds the [`TreeBuilderConfig`] this REPL run normalizes its output with, from repeated
+/// `--skip RULE` / `--transparent RULE` flags and an optional `--collapse` flag. Unlike most of
+/// this crate's `RuleType`s, `pest_vm`'s `Rule = &str`, so a rule can be taken straight off the
+/// command line with no parsing into an enum required.
+fn parse_config(mut args: impl Iterator<Item = String>) -> TreeBuilderConfig<&'static str> {
+    let mut config = TreeBuilderConfig::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--skip" => {
+                let rule = args
+                    .next()
+                    .unwrap_or_else(|| panic!("--skip requires a rule name"));
+                let rule: &'static str = rule.leak();
+                config = config.skip_rule(rule);
+            }
+            "--transparent" => {
+                let rule = args
+                    .next()
+                    .unwrap_or_else(|| panic!("--transparent requires a rule name"));
+                let rule: &'static str = rule.leak();
+                config = config.transparent_rule(rule);
+            }
+            "--collapse" => config = config.with_collapse_single_child_chains(true),
+            other => panic!("Unrecognized argument {other:?}"),
+        }
+    }
+    config
+}
+
+/// Reads lines from `stdin` until a blank line or EOF, so a snippet spanning several lines
+/// (a function body, a block) can be entered before parsing is attempted. Returns `None` once
+/// there is nothing left to read at all.
+fn read_snippet(stdin: &io::Stdin) -> Option<String> {
+    let mut lines = stdin.lock().lines();
+    let mut snippet = String::new();
+    let mut read_any = false;
+    let mut at_eof = false;
+    loop {
+        match lines.next() {
+            Some(Ok(line)) if line.trim().is_empty() => break,
+            Some(Ok(line)) => {
+                read_any = true;
+                snippet.push_str(&line);
+                snippet.push('\n');
+            }
+            Some(Err(_)) | None => {
+                at_eof = true;
+                break;
+            }
+        }
+    }
+    (read_any || !at_eof).then_some(snippet)
+}
+
+fn print_parse(
+    vm: &Vm,
+    start_rule: &str,
+    snippet: &str,
+    config: &TreeBuilderConfig<&'static str>,
+    color: Option<Color>,
+) {
+    let mut pairs = match vm.parse(start_rule, snippet) {
+        Ok(pairs) => pairs,
+        Err(error) => {
+            eprintln!("Parse error: {error}");
+            return;
+        }
+    };
+    let Some(pair) = pairs.next() else {
+        eprintln!("Grammar produced no pairs for rule {start_rule:?}");
+        return;
+    };
+    let expression = match Expression::try_from_code_with(pair, config) {
+        Ok(Some(expression)) => expression,
+        Ok(None) => {
+            eprintln!("Root pair was skipped or made transparent by --skip/--transparent");
+            return;
+        }
+        Err(error) => {
+            eprintln!("Failed to build expression: {error:?}");
+            return;
+        }
+    };
+    let mut output = String::new();
+    let mut formatter = match color {
+        Some(color) => ExpressionFormatter::from_color(&mut output, color),
+        None => ExpressionFormatter::from_defaults(&mut output),
+    };
+    formatter
+        .fmt(&expression)
+        .expect("formatting to a String cannot fail");
+    println!("{output}");
+}
@@ -1,6 +1,7 @@
 use crate::parser::Rule;
 use colored::{Color, Colorize};
 use pest::{iterators::Pair, RuleType};
+use std::collections::HashSet;
 use std::fmt::{Display, Result as FmtResult, Write};
 
 #[derive(Debug)]
@@ -12,6 +13,85 @@ impl ModelError {
     }
 }
 
+/// Resolves a byte offset into `code` to a 1-indexed `(line, column)`. `byte_offset` is expected
+/// to come from parsing `code` itself (e.g. `code` must be byte-identical to whatever string a
+/// [`Expression`]'s span was recorded against) -- but an offset past the end, or off a char
+/// boundary, is handled by falling back to the whole string rather than panicking, since a
+/// mismatched span shouldn't crash the mismatch reporter that's diagnosing it.
+fn byte_offset_to_line_col(code: &str, byte_offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut column = 1;
+    for ch in code.get(..byte_offset).unwrap_or(code).chars() {
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+/// Escapes a terminal value for embedding between `"`s in s-expression syntax, so that `"`, `\`,
+/// and newlines round-trip through [`unescape_value`] instead of corrupting the surrounding
+/// syntax or being silently dropped.
+fn escape_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
+
+/// Inverse of [`escape_value`]. Only the escapes [`escape_value`] itself produces (`\\`, `\"`,
+/// `\n`, `\r`, `\t`) are interpreted; any other `\x` is left exactly as written, backslash
+/// included, rather than dropping it. This is required for backward compatibility: pre-chunk0-5
+/// expected blocks took `rule_value` verbatim, so a hand-written value like `C:\folder` (a
+/// backslash with no escaping intent) must keep reading as `C:\folder`, not `C:folder`.
+fn unescape_value(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('"') => unescaped.push('"'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => {
+                unescaped.push('\\');
+                unescaped.push(other);
+            }
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
+/// Formats a rule for use as a node name in s-expression syntax. Most `RuleType`s `Debug`-format
+/// as a bare identifier (e.g. `function_definition`), but `pest_vm`'s dynamically-typed `Rule =
+/// &str` debug-formats as a quoted string (`"function_definition"`) like any other `&str` would --
+/// left as-is, those embedded quotes would corrupt the surrounding `(name: "value")` syntax. Strip
+/// one matching pair of leading/trailing quotes if present, leaving every other `RuleType` alone.
+fn format_rule_name<R: RuleType>(rule: R) -> String {
+    let name = format!("{:?}", rule);
+    name.strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+        .map(str::to_owned)
+        .unwrap_or(name)
+}
+
 fn assert_rule<'a>(pair: Pair<'a, Rule>, rule: Rule) -> Result<Pair<'a, Rule>, ModelError> {
     if pair.as_rule() == rule {
         Ok(pair)
@@ -28,24 +108,138 @@ pub enum Expression {
     Terminal {
         name: String,
         value: Option<String>,
+        /// Byte offsets `(start, end)` into the source code this node was parsed from. `None`
+        /// for expressions parsed from an expected s-expression, which has no associated source.
+        span: Option<(usize, usize)>,
     },
     NonTerminal {
         name: String,
         children: Vec<Expression>,
+        /// Byte offsets `(start, end)` into the source code this node was parsed from. `None`
+        /// for expressions parsed from an expected s-expression, which has no associated source.
+        span: Option<(usize, usize)>,
     },
+    /// A `(_)` placeholder in an expected s-expression: matches any single subtree regardless of
+    /// its name or children.
+    Wildcard,
+    /// A trailing `...` in a `sub_expressions` list: matches zero or more remaining siblings.
+    /// Only meaningful as the last entry of a `NonTerminal`'s `children`; never produced by
+    /// [`Expression::try_from_code`].
+    Ellipsis,
 }
 
 impl Expression {
-    pub fn name(&self) -> &String {
+    /// The rule name of this node, or `None` for [`Self::Wildcard`]/[`Self::Ellipsis`], which
+    /// stand in for a node rather than naming one.
+    pub fn name(&self) -> Option<&str> {
         match self {
-            Self::Terminal { name, value: _ } => name,
-            Self::NonTerminal { name, children: _ } => name,
+            Self::Terminal { name, .. } => Some(name),
+            Self::NonTerminal { name, .. } => Some(name),
+            Self::Wildcard | Self::Ellipsis => None,
+        }
+    }
+
+    /// The span of source code this node was parsed from, if any. Only expressions produced by
+    /// [`Expression::try_from_code`] or [`Expression::try_from_code_with`] carry a span.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Terminal { span, .. } => *span,
+            Self::NonTerminal { span, .. } => *span,
+            Self::Wildcard | Self::Ellipsis => None,
+        }
+    }
+
+    /// Resolves this node's span to a 1-indexed `(line, column)` within `code`, the same source
+    /// string that was parsed to produce it.
+    pub fn line_col(&self, code: &str) -> Option<(usize, usize)> {
+        self.span()
+            .map(|(start, _)| byte_offset_to_line_col(code, start))
+    }
+
+    /// Compares an expected expression (`self`) against an actual one, honoring wildcard
+    /// placeholders. Unlike [`PartialEq`], `(_)` matches any subtree, a terminal value of `_`
+    /// matches any string, and a trailing `...` in a child list matches any remaining siblings.
+    pub fn matches(&self, actual: &Expression) -> bool {
+        match self {
+            Self::Wildcard => true,
+            Self::Ellipsis => true,
+            Self::Terminal { name, value, .. } => match actual {
+                Self::Terminal {
+                    name: actual_name,
+                    value: actual_value,
+                    ..
+                } => {
+                    name == actual_name
+                        && match value.as_deref() {
+                            Some("_") => true,
+                            Some(value) => Some(value) == actual_value.as_deref(),
+                            None => actual_value.is_none(),
+                        }
+                }
+                _ => false,
+            },
+            Self::NonTerminal { name, children, .. } => match actual {
+                Self::NonTerminal {
+                    name: actual_name,
+                    children: actual_children,
+                    ..
+                } => name == actual_name && Self::children_match(children, actual_children),
+                _ => false,
+            },
+        }
+    }
+
+    /// Matches an expected child list against an actual one. If the expected list ends with
+    /// [`Expression::Ellipsis`], only the children before it must match a prefix of `actual`;
+    /// otherwise both lists must match pairwise and have equal length.
+    fn children_match(expected: &[Expression], actual: &[Expression]) -> bool {
+        match expected.split_last() {
+            Some((Self::Ellipsis, prefix)) => {
+                prefix.len() <= actual.len()
+                    && prefix
+                        .iter()
+                        .zip(actual.iter())
+                        .all(|(expected, actual)| expected.matches(actual))
+            }
+            _ => {
+                expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(actual.iter())
+                        .all(|(expected, actual)| expected.matches(actual))
+            }
         }
     }
 }
 
 impl Expression {
+    /// Builds an expected tree from a parsed s-expression. Like every other `Rule::*` variant
+    /// referenced in this function, `Rule::wildcard` and `Rule::ellipsis` must exist in this
+    /// crate's `.pest` grammar file. That file isn't part of this source tree (none of
+    /// `test_name`/`code_block`/`div`/`code`/`expression`/`rule_name`/`sub_expressions`/
+    /// `rule_value_str`/`rule_value` -- every other `Rule::*` this module already depends on --
+    /// has a `.pest` definition checked in here either, predating this whole series), so the two
+    /// new productions can't be added to it from this tree. For whoever does have that file, the
+    /// additions this feature needs are:
+    ///
+    /// ```pest
+    /// wildcard = { "(_)" }
+    /// ellipsis = { "..." }
+    /// ```
+    ///
+    /// spliced in as additional alternatives of whatever rule `sub_expressions`' children
+    /// currently enumerate (alongside `expression`), so `(_)` and a trailing `...` parse as
+    /// siblings of ordinary `(rule_name ...)` entries. No change is needed for the value-`_`
+    /// wildcard (`(identifier: _)`): it's handled entirely on the Rust side in [`Self::matches`],
+    /// since `rule_value` already has to accept identifier-like characters (`_` included) for
+    /// ordinary values like `x` or `int`.
     pub fn try_from_sexpr<'a>(pair: Pair<'a, Rule>) -> Result<Self, ModelError> {
+        if pair.as_rule() == Rule::wildcard {
+            return Ok(Self::Wildcard);
+        }
+        if pair.as_rule() == Rule::ellipsis {
+            return Ok(Self::Ellipsis);
+        }
         let mut inner = pair.into_inner();
         let name = inner
             .next()
@@ -53,7 +247,11 @@ impl Expression {
             .and_then(|pair| assert_rule(pair, Rule::rule_name))
             .map(|pair| pair.as_str().to_owned())?;
         match inner.next() {
-            None => Ok(Self::Terminal { name, value: None }),
+            None => Ok(Self::Terminal {
+                name,
+                value: None,
+                span: None,
+            }),
             Some(pair) => match pair.as_rule() {
                 Rule::sub_expressions => {
                     let children: Result<Vec<Expression>, ModelError> = pair
@@ -63,6 +261,7 @@ impl Expression {
                     Ok(Self::NonTerminal {
                         name,
                         children: children?,
+                        span: None,
                     })
                 }
                 Rule::rule_value_str => {
@@ -72,10 +271,14 @@ impl Expression {
                         .map(|pair| assert_rule(pair, Rule::rule_value))
                         .transpose()
                         .map(|opt| {
-                            opt.map(|pair| pair.as_str().to_owned())
+                            opt.map(|pair| unescape_value(pair.as_str()))
                                 .or_else(|| Some(String::new()))
                         })?;
-                    Ok(Self::Terminal { name, value })
+                    Ok(Self::Terminal {
+                        name,
+                        value,
+                        span: None,
+                    })
                 }
                 other => Err(ModelError(format!("Unexpected rule {:?}", other))),
             },
@@ -83,7 +286,9 @@ impl Expression {
     }
 
     pub fn try_from_code<'a, R: RuleType>(pair: Pair<'a, R>) -> Result<Self, ModelError> {
-        let name = format!("{:?}", pair.as_rule());
+        let name = format_rule_name(pair.as_rule());
+        let pair_span = pair.as_span();
+        let span = Some((pair_span.start(), pair_span.end()));
         let value = pair.as_str();
         let children: Result<Vec<Expression>, ModelError> = pair
             .into_inner()
@@ -93,14 +298,313 @@ impl Expression {
             Ok(children) if children.is_empty() => Ok(Self::Terminal {
                 name,
                 value: Some(value.to_owned()),
+                span,
             }),
             Ok(children) => Ok(Self::NonTerminal {
                 name,
-                children: children,
+                children,
+                span,
             }),
             Err(e) => Err(e),
         }
     }
+
+    /// Like [`Self::try_from_code`], but normalizes the tree with `config` first: rules in
+    /// `config.skip` are omitted entirely, children of rules in `config.transparent` are spliced
+    /// into their parent, and (if enabled) single-child chains are collapsed into the innermost
+    /// node. Returns `Ok(None)` if `pair`'s own rule is skipped, or is transparent with no
+    /// normalized children, since that leaves no node to return. A transparent root with exactly
+    /// one normalized child returns that child; one with several returns an error.
+    pub fn try_from_code_with<'a, R: RuleType>(
+        pair: Pair<'a, R>,
+        config: &TreeBuilderConfig<R>,
+    ) -> Result<Option<Self>, ModelError> {
+        let mut nodes = Self::try_from_code_with_flattened(pair, config)?;
+        match nodes.len() {
+            0 => Ok(None),
+            1 => Ok(nodes.pop()),
+            _ => Err(ModelError::from_str(
+                "Root pair was spliced into multiple nodes by a transparent rule",
+            )),
+        }
+    }
+
+    /// Builds the normalized nodes for `pair`: empty if its rule is skipped, the (possibly
+    /// multiple) normalized children if its rule is transparent, otherwise a single node.
+    fn try_from_code_with_flattened<'a, R: RuleType>(
+        pair: Pair<'a, R>,
+        config: &TreeBuilderConfig<R>,
+    ) -> Result<Vec<Self>, ModelError> {
+        let rule = pair.as_rule();
+        if config.skip.contains(&rule) {
+            return Ok(Vec::new());
+        }
+        if config.transparent.contains(&rule) {
+            let mut children = Vec::new();
+            for pair in pair.into_inner() {
+                children.extend(Self::try_from_code_with_flattened(pair, config)?);
+            }
+            return Ok(children);
+        }
+        let name = format_rule_name(rule);
+        let pair_span = pair.as_span();
+        let span = Some((pair_span.start(), pair_span.end()));
+        let value = pair.as_str().to_owned();
+        let mut children = Vec::new();
+        for pair in pair.into_inner() {
+            children.extend(Self::try_from_code_with_flattened(pair, config)?);
+        }
+        let node = if children.is_empty() {
+            Self::Terminal {
+                name,
+                value: Some(value),
+                span,
+            }
+        } else if config.collapse_single_child_chains && children.len() == 1 {
+            children.pop().expect("checked len() == 1 above")
+        } else {
+            Self::NonTerminal {
+                name,
+                children,
+                span,
+            }
+        };
+        Ok(vec![node])
+    }
+}
+
+/// Configuration for normalizing a parse tree built by [`Expression::try_from_code_with`], so
+/// expected s-expressions don't need to account for a grammar's whitespace, punctuation, or
+/// silent pass-through rules.
+#[derive(Clone, Debug)]
+pub struct TreeBuilderConfig<R: RuleType> {
+    /// Rules whose nodes (and their subtrees) are omitted entirely.
+    pub skip: HashSet<R>,
+    /// Rules whose children are spliced into the parent instead of producing a node of their own.
+    pub transparent: HashSet<R>,
+    /// Fold `A -> B -> C` single-child chains into the innermost meaningful node.
+    pub collapse_single_child_chains: bool,
+}
+
+impl<R: RuleType> Default for TreeBuilderConfig<R> {
+    fn default() -> Self {
+        Self {
+            skip: HashSet::new(),
+            transparent: HashSet::new(),
+            collapse_single_child_chains: false,
+        }
+    }
+}
+
+impl<R: RuleType> TreeBuilderConfig<R> {
+    pub fn skip_rule(mut self, rule: R) -> Self {
+        self.skip.insert(rule);
+        self
+    }
+
+    pub fn transparent_rule(mut self, rule: R) -> Self {
+        self.transparent.insert(rule);
+        self
+    }
+
+    pub fn with_collapse_single_child_chains(mut self, collapse: bool) -> Self {
+        self.collapse_single_child_chains = collapse;
+        self
+    }
+}
+
+/// A node-by-node comparison of an expected [`Expression`] against an actual one, produced by
+/// [`Expression::diff`]. Mirrors the shape of [`Expression`] but annotates where the two trees
+/// agree, where a value differs, and where a subtree was only expected or only actually parsed.
+#[derive(Clone, Debug)]
+pub enum ExpressionDiff {
+    Terminal {
+        name: String,
+        expected_value: Option<String>,
+        /// `Some(value)` when the actual terminal's value differs from `expected_value`, unless
+        /// `expected_value` is the `_` wildcard.
+        actual_value: Option<Option<String>>,
+        /// The actual node's span, used to localize a mismatch in the source that was parsed.
+        actual_span: Option<(usize, usize)>,
+    },
+    NonTerminal {
+        name: String,
+        children: Vec<ExpressionDiff>,
+    },
+    /// A subtree that only appears in the expected tree.
+    Deleted(Expression),
+    /// A subtree that only appears in the actual tree.
+    Inserted(Expression),
+    /// Two subtrees at the same position whose root rule names differ entirely.
+    Replaced {
+        expected: Expression,
+        actual: Expression,
+    },
+    /// An actual subtree that a placeholder ([`Expression::Wildcard`] or a trailing
+    /// [`Expression::Ellipsis`]) already considers satisfied. Rendered without any red/green
+    /// annotation, since [`Expression::matches`] would not flag it as a mismatch.
+    Matched(Expression),
+}
+
+impl ExpressionDiff {
+    /// Finds the span of the first actual node this diff considers a mismatch, so an assertion
+    /// failure can be localized to roughly where the divergence begins. Returns `None` if `self`
+    /// is entirely a match (or carries no span, e.g. an expected-only deletion).
+    fn first_mismatch_span(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Terminal {
+                actual_value: Some(_),
+                actual_span,
+                ..
+            } => *actual_span,
+            Self::Terminal { .. } => None,
+            Self::NonTerminal { children, .. } => {
+                children.iter().find_map(Self::first_mismatch_span)
+            }
+            Self::Deleted(_) => None,
+            Self::Inserted(expression) => expression.span(),
+            Self::Replaced { actual, .. } => actual.span(),
+            Self::Matched(_) => None,
+        }
+    }
+}
+
+impl Expression {
+    /// Computes a structural diff between an expected tree and the actual tree produced by
+    /// parsing. Children are aligned with a longest-common-subsequence over their `name`s so
+    /// that a single spurious or missing node doesn't cascade into the rest of the subtree being
+    /// marked as mismatched. A [`Self::Wildcard`] (or a value of `_` on an expected terminal)
+    /// never produces a mismatch here, mirroring [`Self::matches`].
+    pub fn diff(expected: &Expression, actual: &Expression) -> ExpressionDiff {
+        match (expected, actual) {
+            (Self::Wildcard, actual) | (Self::Ellipsis, actual) => {
+                ExpressionDiff::Matched(actual.clone())
+            }
+            (
+                Self::Terminal {
+                    name: expected_name,
+                    value: expected_value,
+                    ..
+                },
+                Self::Terminal {
+                    name: actual_name,
+                    value: actual_value,
+                    span: actual_span,
+                },
+            ) if expected_name == actual_name => ExpressionDiff::Terminal {
+                name: expected_name.clone(),
+                expected_value: expected_value.clone(),
+                actual_value: if expected_value.as_deref() == Some("_")
+                    || expected_value == actual_value
+                {
+                    None
+                } else {
+                    Some(actual_value.clone())
+                },
+                actual_span: *actual_span,
+            },
+            (
+                Self::NonTerminal {
+                    name: expected_name,
+                    children: expected_children,
+                    ..
+                },
+                Self::NonTerminal {
+                    name: actual_name,
+                    children: actual_children,
+                    ..
+                },
+            ) if expected_name == actual_name => ExpressionDiff::NonTerminal {
+                name: expected_name.clone(),
+                children: Self::diff_children(expected_children, actual_children),
+            },
+            _ => ExpressionDiff::Replaced {
+                expected: expected.clone(),
+                actual: actual.clone(),
+            },
+        }
+    }
+
+    /// A stable key used only to align expected/actual child lists in [`Self::diff_children`].
+    /// Named nodes key on their rule name, same as [`Self::name`]; [`Self::Wildcard`] and
+    /// [`Self::Ellipsis`] carry no name (`name()` returns `None` for them) but can legally appear
+    /// in an expected tree since chunk0-2, so they get a synthetic key instead — one that never
+    /// collides with a real rule name and never matches an actual (parsed) node, since actual
+    /// trees never contain either variant.
+    fn diff_key(&self) -> &str {
+        match self {
+            Self::Terminal { name, .. } | Self::NonTerminal { name, .. } => name,
+            Self::Wildcard => "\0wildcard",
+            Self::Ellipsis => "\0ellipsis",
+        }
+    }
+
+    /// Aligns two child lists, recursing into matched pairs and marking unmatched
+    /// expected/actual children as deletions/insertions respectively. Mirrors
+    /// [`Self::children_match`]'s handling of a trailing [`Self::Ellipsis`]: when `expected` ends
+    /// with one and there are enough actual children to cover the rest of `expected`, every actual
+    /// child the ellipsis stands in for is reported as a neutral match rather than being diffed.
+    fn diff_children(expected: &[Expression], actual: &[Expression]) -> Vec<ExpressionDiff> {
+        if let Some((Self::Ellipsis, prefix)) = expected.split_last() {
+            if prefix.len() <= actual.len() {
+                let mut diffs: Vec<ExpressionDiff> = prefix
+                    .iter()
+                    .zip(actual.iter())
+                    .map(|(expected, actual)| Self::diff(expected, actual))
+                    .collect();
+                diffs.extend(
+                    actual[prefix.len()..]
+                        .iter()
+                        .cloned()
+                        .map(ExpressionDiff::Matched),
+                );
+                return diffs;
+            }
+        }
+        Self::diff_children_lcs(expected, actual)
+    }
+
+    /// A key-equality check used only to align expected/actual child lists in
+    /// [`Self::diff_children_lcs`]: a [`Self::Wildcard`] aligns with any actual child (mirroring
+    /// how [`Self::matches`] treats it), everything else aligns by [`Self::diff_key`].
+    fn diff_keys_align(expected: &Expression, actual: &Expression) -> bool {
+        matches!(expected, Self::Wildcard) || expected.diff_key() == actual.diff_key()
+    }
+
+    /// Aligns two child lists with a longest-common-subsequence keyed on [`Self::diff_keys_align`],
+    /// recursing into matched pairs and marking unmatched expected/actual children as
+    /// deletions/insertions respectively.
+    fn diff_children_lcs(expected: &[Expression], actual: &[Expression]) -> Vec<ExpressionDiff> {
+        let (n, m) = (expected.len(), actual.len());
+        let mut lengths = vec![vec![0usize; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lengths[i][j] = if Self::diff_keys_align(&expected[i], &actual[j]) {
+                    lengths[i + 1][j + 1] + 1
+                } else {
+                    lengths[i + 1][j].max(lengths[i][j + 1])
+                };
+            }
+        }
+        let mut diffs = Vec::with_capacity(n.max(m));
+        let (mut i, mut j) = (0, 0);
+        while i < n && j < m {
+            if Self::diff_keys_align(&expected[i], &actual[j]) {
+                diffs.push(Self::diff(&expected[i], &actual[j]));
+                i += 1;
+                j += 1;
+            } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+                diffs.push(ExpressionDiff::Deleted(expected[i].clone()));
+                i += 1;
+            } else {
+                diffs.push(ExpressionDiff::Inserted(actual[j].clone()));
+                j += 1;
+            }
+        }
+        diffs.extend(expected[i..n].iter().cloned().map(ExpressionDiff::Deleted));
+        diffs.extend(actual[j..m].iter().cloned().map(ExpressionDiff::Inserted));
+        diffs
+    }
 }
 
 pub struct ExpressionFormatter<'a> {
@@ -120,6 +624,28 @@ impl<'a> ExpressionFormatter<'a> {
         }
     }
 
+    /// Like [`Self::from_defaults`], but renders every node in `color` (e.g. for REPL output on a
+    /// TTY, where plain s-expressions are easier to read with a little color).
+    pub fn from_color(writer: &'a mut dyn Write, color: Color) -> Self {
+        Self {
+            writer,
+            indent: "  ",
+            level: 0,
+            color: Some(color),
+        }
+    }
+
+    /// Runs `f` with `color` overriding the formatter's color for its duration, restoring the
+    /// previous color afterwards. This lets [`Self::fmt_diff`] give each node its own color
+    /// within a single `fmt` pass instead of rendering expected/actual as separate dumps.
+    fn with_color<T>(&mut self, color: Option<Color>, f: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = self.color;
+        self.color = color;
+        let result = f(self);
+        self.color = previous;
+        result
+    }
+
     pub(crate) fn write_indent(&mut self) -> FmtResult {
         for _ in 0..self.level {
             self.writer.write_str(self.indent)?;
@@ -146,22 +672,32 @@ impl<'a> ExpressionFormatter<'a> {
     }
 
     pub fn fmt(&mut self, expression: &Expression) -> FmtResult {
+        if let Expression::Ellipsis = expression {
+            self.write_indent()?;
+            return self.write_str("...");
+        }
         self.write_indent()?;
         self.write_char('(')?;
         match expression {
-            Expression::Terminal { name, value } => {
+            Expression::Wildcard => {
+                self.write_char('_')?;
+                self.write_char(')')?;
+            }
+            Expression::Ellipsis => unreachable!("handled above"),
+            Expression::Terminal { name, value, .. } => {
                 self.write_str(name)?;
                 if let Some(value) = value {
-                    self.write_str(": ")?;
-                    self.write_str(value)?;
+                    self.write_str(": \"")?;
+                    self.write_str(&escape_value(value))?;
+                    self.write_char('"')?;
                 }
                 self.write_char(')')?;
             }
-            Expression::NonTerminal { name, children } if children.is_empty() => {
+            Expression::NonTerminal { name, children, .. } if children.is_empty() => {
                 self.write_str(name)?;
                 self.write_char(')')?;
             }
-            Expression::NonTerminal { name, children } => {
+            Expression::NonTerminal { name, children, .. } => {
                 self.write_str(name)?;
                 self.write_newline()?;
                 self.level += 1;
@@ -176,6 +712,87 @@ impl<'a> ExpressionFormatter<'a> {
         }
         Ok(())
     }
+
+    /// Renders an [`ExpressionDiff`] as a single tree: matching nodes print in the formatter's
+    /// base color, deleted (expected-only) subtrees print red, inserted (actual-only) subtrees
+    /// print green, and a terminal whose value differs prints the expected value in red and the
+    /// actual value in green.
+    pub fn fmt_diff(&mut self, diff: &ExpressionDiff) -> FmtResult {
+        match diff {
+            ExpressionDiff::Matched(expression) => self.fmt(expression),
+            ExpressionDiff::Deleted(expression) => {
+                self.with_color(Some(Color::Red), |fmt| fmt.fmt(expression))
+            }
+            ExpressionDiff::Inserted(expression) => {
+                self.with_color(Some(Color::Green), |fmt| fmt.fmt(expression))
+            }
+            ExpressionDiff::Replaced { expected, actual } => {
+                self.with_color(Some(Color::Red), |fmt| fmt.fmt(expected))?;
+                self.write_newline()?;
+                self.with_color(Some(Color::Green), |fmt| fmt.fmt(actual))
+            }
+            ExpressionDiff::Terminal {
+                name,
+                expected_value,
+                actual_value,
+                ..
+            } => {
+                self.write_indent()?;
+                self.write_char('(')?;
+                self.write_str(name)?;
+                match (expected_value, actual_value) {
+                    (None, None) | (None, Some(None)) => {}
+                    (None, Some(Some(actual))) => {
+                        self.write_str(": \"")?;
+                        let actual = escape_value(actual);
+                        self.with_color(Some(Color::Green), |fmt| fmt.write_str(&actual))?;
+                        self.write_char('"')?;
+                    }
+                    (Some(expected), None) => {
+                        self.write_str(": \"")?;
+                        self.write_str(&escape_value(expected))?;
+                        self.write_char('"')?;
+                    }
+                    (Some(expected), Some(actual)) => {
+                        self.write_str(": \"")?;
+                        let expected = escape_value(expected);
+                        self.with_color(Some(Color::Red), |fmt| fmt.write_str(&expected))?;
+                        self.write_str("\"/\"")?;
+                        match actual {
+                            Some(actual) => {
+                                let actual = escape_value(actual);
+                                self.with_color(Some(Color::Green), |fmt| fmt.write_str(&actual))?
+                            }
+                            None => self
+                                .with_color(Some(Color::Green), |fmt| fmt.write_str("<missing>"))?,
+                        }
+                        self.write_char('"')?;
+                    }
+                }
+                self.write_char(')')
+            }
+            ExpressionDiff::NonTerminal { name, children } if children.is_empty() => {
+                self.write_indent()?;
+                self.write_char('(')?;
+                self.write_str(name)?;
+                self.write_char(')')
+            }
+            ExpressionDiff::NonTerminal { name, children } => {
+                self.write_indent()?;
+                self.write_char('(')?;
+                self.write_str(name)?;
+                self.write_newline()?;
+                self.level += 1;
+                for child in children {
+                    self.fmt_diff(child)?;
+                    self.write_newline()?;
+                }
+                self.level -= 1;
+                self.write_indent()?;
+                self.write_char(')')
+            }
+        }
+    }
 }
 
 impl Display for Expression {
@@ -184,6 +801,12 @@ impl Display for Expression {
     }
 }
 
+impl Display for ExpressionDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> FmtResult {
+        ExpressionFormatter::from_defaults(f).fmt_diff(self)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TestCase {
     pub name: String,
@@ -223,6 +846,67 @@ impl TestCase {
             expression: Expression::try_from_sexpr(expression)?,
         })
     }
+
+    /// Describes where in `self.code` a mismatched actual node came from, for use in assertion
+    /// failure messages, e.g. `mismatch at line 3:5 near "return 1"`. `actual_span` must have come
+    /// from parsing `self.code` itself byte-for-byte (as [`Self::build_actual`] does) -- parsing
+    /// any other string (e.g. the untrimmed source a code block was cut from) can produce spans
+    /// that land past the end of `self.code` or off a char boundary, which this degrades
+    /// gracefully for rather than panicking on.
+    pub fn describe_mismatch(&self, actual_span: Option<(usize, usize)>) -> String {
+        let Some((start, end)) = actual_span else {
+            return "mismatch (no span available)".to_owned();
+        };
+        let (line, column) = byte_offset_to_line_col(&self.code, start);
+        let snippet = self.code.get(start..end).unwrap_or_default();
+        format!("mismatch at line {line}:{column} near {snippet:?}")
+    }
+
+    /// Asserts that `actual` satisfies this test case's expected expression, honoring wildcard and
+    /// ellipsis placeholders via [`Expression::matches`] rather than requiring an exact
+    /// [`PartialEq`]. This is the comparison the rest of the test harness runs for every parsed
+    /// test case. On a mismatch, the error is the colored tree diff prefixed with the source
+    /// location of the first node that failed to match.
+    pub fn assert_matches(&self, actual: &Expression) -> Result<(), String> {
+        if self.expression.matches(actual) {
+            return Ok(());
+        }
+        let diff = Expression::diff(&self.expression, actual);
+        let location = self.describe_mismatch(diff.first_mismatch_span());
+        Err(format!("{location}\n{diff}"))
+    }
+
+    /// Builds the actual parse tree for this test case's code from `pair` (the result of parsing
+    /// `self.code` with the grammar under test), normalizing it with `config` so a project can
+    /// register its whitespace/comment/pass-through rules once and have every test compare
+    /// against the same normalized shape.
+    pub fn build_actual<'a, R: RuleType>(
+        &self,
+        pair: Pair<'a, R>,
+        config: &TreeBuilderConfig<R>,
+    ) -> Result<Expression, ModelError> {
+        Expression::try_from_code_with(pair, config)?.ok_or_else(|| {
+            ModelError::from_str("Root pair was skipped or made transparent by TreeBuilderConfig")
+        })
+    }
+
+    /// Rewrites `source` — the `.txt` test file `self` was parsed from — for snapshot/accept mode:
+    /// everything through the second `=======` divider (the name and code blocks) is kept as-is,
+    /// and the expected expression after it is replaced with `actual` formatted via
+    /// [`ExpressionFormatter`]. This relies on that formatter being a lossless inverse of
+    /// [`Expression::try_from_sexpr`], so re-parsing the rewritten file reproduces `actual`.
+    pub fn update_expected(source: &str, actual: &Expression) -> Result<String, ModelError> {
+        const DIVIDER: &str = "=======";
+        let first = source
+            .find(DIVIDER)
+            .ok_or_else(|| ModelError::from_str("Missing first ======= divider"))?;
+        let second = source[first + DIVIDER.len()..]
+            .find(DIVIDER)
+            .map(|offset| first + DIVIDER.len() + offset)
+            .ok_or_else(|| ModelError::from_str("Missing second ======= divider"))?;
+        let head = &source[..second + DIVIDER.len()];
+        Ok(format!("{head}\n\n{actual}\n"))
+    }
 }
 
 #[cfg(test)]
@@ -239,7 +923,7 @@ mod tests {
         expected_name: &'a str,
     ) -> &'a Vec<Expression> {
         match expression {
-            Expression::NonTerminal { name, children } => {
+            Expression::NonTerminal { name, children, .. } => {
                 assert_eq!(name, expected_name);
                 children
             }
@@ -249,7 +933,7 @@ mod tests {
 
     fn assert_terminal(expression: &Expression, expected_name: &str, expected_value: Option<&str>) {
         match expression {
-            Expression::Terminal { name, value } => {
+            Expression::Terminal { name, value, .. } => {
                 assert_eq!(name, expected_name);
                 match (value, expected_value) {
                     (Some(actual), Some(expected)) => assert_eq!(actual, expected),
@@ -314,4 +998,321 @@ mod tests {
         assert_terminal(&children[0], "number", Some("1"));
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_update_expected_round_trips_through_display() -> Result<(), Error<Rule>> {
+        let original = indoc! {r#"
+        My Test
+
+        =======
+
+        fn x() int {
+          return 1;
+        }
+
+        =======
+
+        (source_file
+          (function_definition
+            (identifier: "x")
+            (parameter_list)
+            (primitive_type: "int")
+            (block
+              (return_statement
+                (number: "1")
+              )
+            )
+          )
+        )
+        "#};
+        let parse = |text: &str| -> Result<TestCase, Error<Rule>> {
+            TestParser::parse(text)
+                .map_err(|source| Error::Parser { source })
+                .and_then(|pair| {
+                    TestCase::try_from_pair(pair).map_err(|source| Error::Model { source })
+                })
+        };
+        let test_case = parse(original)?;
+        let rewritten = TestCase::update_expected(original, &test_case.expression)
+            .expect("update_expected should succeed on a well-formed test file");
+        let reparsed = parse(&rewritten)?;
+        assert_eq!(reparsed.name, test_case.name);
+        assert_eq!(reparsed.code, test_case.code);
+        assert_eq!(
+            reparsed.expression.to_string(),
+            test_case.expression.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_name_is_none_for_wildcard_and_ellipsis() {
+        assert_eq!(Expression::Wildcard.name(), None);
+        assert_eq!(Expression::Ellipsis.name(), None);
+        assert_eq!(terminal("identifier", Some("x")).name(), Some("identifier"));
+    }
+
+    use super::{escape_value, format_rule_name, unescape_value, ExpressionDiff};
+
+    #[test]
+    fn test_format_rule_name_strips_quotes_from_str_rules() {
+        let rule: &str = "function_definition";
+        assert_eq!(format_rule_name(rule), "function_definition");
+    }
+
+    #[test]
+    fn test_escape_value_round_trips_quotes_backslashes_and_newlines() {
+        let values = [
+            r#"he said "hi""#,
+            r"C:\path\to\file",
+            "line one\nline two",
+            "a\tb\rc",
+            "plain",
+        ];
+        for value in values {
+            let escaped = escape_value(value);
+            assert!(
+                !escaped.contains('\n'),
+                "escaped value must not contain a literal newline: {escaped:?}"
+            );
+            assert_eq!(unescape_value(&escaped), value);
+        }
+    }
+
+    #[test]
+    fn test_unescape_value_preserves_unrecognized_backslash_sequences() {
+        // A hand-written expected block predating chunk0-5's escaping took `rule_value` verbatim,
+        // so a backslash with no escaping intent (e.g. a Windows path) must not be interpreted --
+        // and must not have its backslash silently dropped either.
+        assert_eq!(unescape_value(r"C:\folder"), r"C:\folder");
+        assert_eq!(unescape_value(r"\d+"), r"\d+");
+    }
+
+    fn terminal(name: &str, value: Option<&str>) -> Expression {
+        Expression::Terminal {
+            name: name.to_owned(),
+            value: value.map(str::to_owned),
+            span: None,
+        }
+    }
+
+    fn nonterminal(name: &str, children: Vec<Expression>) -> Expression {
+        Expression::NonTerminal {
+            name: name.to_owned(),
+            children,
+            span: None,
+        }
+    }
+
+    #[test]
+    fn test_diff_matches_value_mismatch_and_extra_child() {
+        let expected = nonterminal(
+            "block",
+            vec![
+                terminal("number", Some("1")),
+                terminal("identifier", Some("x")),
+            ],
+        );
+        let actual = nonterminal(
+            "block",
+            vec![
+                terminal("number", Some("2")),
+                terminal("identifier", Some("x")),
+                terminal("comment", Some("// trailing")),
+            ],
+        );
+        let diff = Expression::diff(&expected, &actual);
+        let children = match &diff {
+            ExpressionDiff::NonTerminal { name, children } => {
+                assert_eq!(name, "block");
+                children
+            }
+            other => panic!("Expected non-terminal diff but found {other:?}"),
+        };
+        assert_eq!(children.len(), 3);
+        match &children[0] {
+            ExpressionDiff::Terminal {
+                name,
+                expected_value,
+                actual_value,
+                ..
+            } => {
+                assert_eq!(name, "number");
+                assert_eq!(expected_value.as_deref(), Some("1"));
+                assert_eq!(actual_value, &Some(Some("2".to_owned())));
+            }
+            other => panic!("Expected terminal diff but found {other:?}"),
+        }
+        match &children[1] {
+            ExpressionDiff::Terminal {
+                name,
+                expected_value,
+                actual_value,
+                ..
+            } => {
+                assert_eq!(name, "identifier");
+                assert_eq!(expected_value.as_deref(), Some("x"));
+                assert_eq!(actual_value, &None);
+            }
+            other => panic!("Expected terminal diff but found {other:?}"),
+        }
+        match &children[2] {
+            ExpressionDiff::Inserted(expression) => {
+                assert_eq!(expression.name(), Some("comment"));
+            }
+            other => panic!("Expected inserted diff but found {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_matches_wildcards_and_trailing_ellipsis() {
+        let expected = nonterminal(
+            "function_definition",
+            vec![
+                terminal("identifier", Some("_")),
+                Expression::Wildcard,
+                Expression::Ellipsis,
+            ],
+        );
+        let actual = nonterminal(
+            "function_definition",
+            vec![
+                terminal("identifier", Some("x")),
+                terminal("parameter_list", None),
+                terminal("primitive_type", Some("int")),
+                nonterminal("block", vec![]),
+            ],
+        );
+        assert!(expected.matches(&actual));
+
+        let too_few_children = nonterminal(
+            "function_definition",
+            vec![
+                terminal("identifier", Some("_")),
+                Expression::Wildcard,
+                Expression::Ellipsis,
+            ],
+        );
+        let actual_missing_identifier = nonterminal("function_definition", vec![]);
+        assert!(!too_few_children.matches(&actual_missing_identifier));
+    }
+
+    #[test]
+    fn test_diff_does_not_panic_on_wildcard_or_ellipsis() {
+        let expected = nonterminal(
+            "function_definition",
+            vec![Expression::Wildcard, Expression::Ellipsis],
+        );
+        let actual = nonterminal(
+            "function_definition",
+            vec![
+                terminal("identifier", Some("x")),
+                terminal("primitive_type", Some("int")),
+            ],
+        );
+        // `expected` matches `actual` here, but diffing it must still not panic: the wildcard and
+        // ellipsis placeholders carry no rule name to align against the real parsed nodes.
+        let diff = Expression::diff(&expected, &actual);
+        match diff {
+            ExpressionDiff::NonTerminal { name, children } => {
+                assert_eq!(name, "function_definition");
+                // The wildcard's placeholder children are satisfied, not mismatched -- they must
+                // render neutrally rather than as a red/green deletion+insertion pair.
+                assert!(
+                    children
+                        .iter()
+                        .all(|child| matches!(child, ExpressionDiff::Matched(_))),
+                    "expected every child to be a neutral match, found {children:?}"
+                );
+            }
+            other => panic!("Expected non-terminal diff but found {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_diff_treats_value_wildcard_and_node_wildcard_as_neutral() {
+        let expected = nonterminal(
+            "function_definition",
+            vec![terminal("identifier", Some("_")), Expression::Wildcard],
+        );
+        let actual = nonterminal(
+            "function_definition",
+            vec![
+                terminal("identifier", Some("anything")),
+                terminal("primitive_type", Some("int")),
+            ],
+        );
+        let diff = Expression::diff(&expected, &actual);
+        match diff {
+            ExpressionDiff::NonTerminal { children, .. } => {
+                assert_eq!(children.len(), 2);
+                match &children[0] {
+                    ExpressionDiff::Terminal { actual_value, .. } => {
+                        assert_eq!(
+                            actual_value, &None,
+                            "a value wildcard must never report a mismatch"
+                        );
+                    }
+                    other => panic!("Expected terminal diff but found {other:?}"),
+                }
+                assert!(matches!(children[1], ExpressionDiff::Matched(_)));
+            }
+            other => panic!("Expected non-terminal diff but found {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_assert_matches_uses_wildcard_matching_and_reports_mismatch() {
+        let test_case = TestCase {
+            name: "assert_matches test".to_owned(),
+            code: "fn x() int {\n  return 1;\n}".to_owned(),
+            expression: nonterminal(
+                "function_definition",
+                vec![terminal("identifier", Some("_")), Expression::Ellipsis],
+            ),
+        };
+        let matching_actual = nonterminal(
+            "function_definition",
+            vec![
+                terminal("identifier", Some("x")),
+                terminal("primitive_type", Some("int")),
+            ],
+        );
+        assert!(test_case.assert_matches(&matching_actual).is_ok());
+
+        let mismatched_actual = nonterminal(
+            "function_definition",
+            vec![terminal("comment", Some("// no identifier here"))],
+        );
+        let error = test_case
+            .assert_matches(&mismatched_actual)
+            .expect_err("a comment where an identifier was expected should not match");
+        assert!(error.contains("mismatch"));
+    }
+
+    #[test]
+    fn test_describe_mismatch_reports_line_and_column() {
+        let test_case = TestCase {
+            name: "span test".to_owned(),
+            code: "fn x() int {\n  return 1;\n}".to_owned(),
+            expression: terminal("source_file", None),
+        };
+        let start = test_case.code.find("return 1").unwrap();
+        let end = start + "return 1".len();
+        let message = test_case.describe_mismatch(Some((start, end)));
+        assert_eq!(message, "mismatch at line 2:3 near \"return 1\"");
+    }
+
+    #[test]
+    fn test_describe_mismatch_does_not_panic_on_out_of_bounds_span() {
+        let test_case = TestCase {
+            name: "span test".to_owned(),
+            code: "short".to_owned(),
+            expression: terminal("source_file", None),
+        };
+        // A span from parsing some other (longer) string than `self.code`, e.g. an untrimmed
+        // code block -- must degrade gracefully rather than panicking on an out-of-bounds slice.
+        let message = test_case.describe_mismatch(Some((100, 108)));
+        assert!(message.starts_with("mismatch at line"));
+    }
+}